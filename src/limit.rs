@@ -0,0 +1,223 @@
+//! A `Service` middleware that bounds the number of concurrently in-flight
+//! calls.
+//!
+//! Pipeline `Server`s and `Client`s have no notion of how many requests
+//! should be allowed in flight at once; a fast producer can make the
+//! `in_flight` queue on the other end grow without bound. `Limit` wraps any
+//! `Service` and admits at most `N` concurrent calls, parking additional
+//! calls until a slot frees up rather than issuing them.
+
+use Service;
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Wraps a `Service`, admitting at most a fixed number of concurrent calls.
+pub struct Limit<S> {
+    inner: Arc<S>,
+    permits: Arc<Permits>,
+}
+
+struct Permits {
+    max: usize,
+    in_flight: AtomicUsize,
+    waiters: Mutex<VecDeque<Task>>,
+}
+
+impl Permits {
+    fn new(max: usize) -> Permits {
+        Permits {
+            max: max,
+            in_flight: AtomicUsize::new(0),
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        loop {
+            let current = self.in_flight.load(Ordering::SeqCst);
+            if current >= self.max {
+                return false;
+            }
+
+            let prev = self.in_flight.compare_and_swap(current, current + 1, Ordering::SeqCst);
+            if prev == current {
+                return true;
+            }
+        }
+    }
+
+    /// Park the current task so that it is woken up the next time a permit
+    /// is released, instead of being left polling `NotReady` forever.
+    fn park_current_task(&self) {
+        self.waiters.lock().unwrap().push_back(task::park());
+    }
+
+    fn release(&self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        // Wake one waiter so it gets a chance to race for the
+        // newly-freed permit. If it loses the race (another caller of
+        // `try_acquire` got there first), it parks again.
+        if let Some(task) = self.waiters.lock().unwrap().pop_front() {
+            task.unpark();
+        }
+    }
+}
+
+impl<S> Limit<S> {
+    /// Admit at most `max` concurrent calls into `inner`; calls made once
+    /// the limit is reached are parked until an admitted call's `LimitFut`
+    /// is dropped. A `LimitFut` that has resolved but not been dropped still
+    /// holds its permit.
+    pub fn new(inner: S, max: usize) -> Limit<S> {
+        Limit {
+            inner: Arc::new(inner),
+            permits: Arc::new(Permits::new(max)),
+        }
+    }
+}
+
+impl<S> Clone for Limit<S> {
+    fn clone(&self) -> Limit<S> {
+        Limit {
+            inner: self.inner.clone(),
+            permits: self.permits.clone(),
+        }
+    }
+}
+
+impl<S: Service> Service for Limit<S> {
+    type Req = S::Req;
+    type Resp = S::Resp;
+    type Error = S::Error;
+    type Fut = LimitFut<S>;
+
+    fn call(&self, request: Self::Req) -> Self::Fut {
+        if self.permits.try_acquire() {
+            LimitFut::Admitted {
+                fut: self.inner.call(request),
+                permits: self.permits.clone(),
+            }
+        } else {
+            LimitFut::Waiting {
+                inner: self.inner.clone(),
+                request: Some(request),
+                permits: self.permits.clone(),
+            }
+        }
+    }
+}
+
+/// The `Future` returned by `Limit::call`.
+pub enum LimitFut<S: Service> {
+    Admitted { fut: S::Fut, permits: Arc<Permits> },
+    Waiting { inner: Arc<S>, request: Option<S::Req>, permits: Arc<Permits> },
+}
+
+impl<S: Service> Future for LimitFut<S> {
+    type Item = S::Resp;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let admitted = match *self {
+            LimitFut::Admitted { ref mut fut, .. } => return fut.poll(),
+            LimitFut::Waiting { ref inner, ref mut request, ref permits } => {
+                if permits.try_acquire() {
+                    let request = request.take().expect("LimitFut polled after completion");
+                    Some(LimitFut::Admitted { fut: inner.call(request), permits: permits.clone() })
+                } else {
+                    permits.park_current_task();
+                    None
+                }
+            }
+        };
+
+        match admitted {
+            Some(state) => {
+                *self = state;
+                match *self {
+                    LimitFut::Admitted { ref mut fut, .. } => fut.poll(),
+                    LimitFut::Waiting { .. } => unreachable!(),
+                }
+            }
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<S: Service> Drop for LimitFut<S> {
+    fn drop(&mut self) {
+        // A `Waiting` future never acquired a permit, so only an
+        // `Admitted` one needs to release it.
+        if let LimitFut::Admitted { ref permits, .. } = *self {
+            permits.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Async, Future};
+    use futures::future::{ok, FutureResult};
+
+    #[test]
+    fn try_acquire_respects_max() {
+        let permits = Permits::new(1);
+
+        assert!(permits.try_acquire());
+        assert!(!permits.try_acquire());
+
+        permits.release();
+        assert!(permits.try_acquire());
+    }
+
+    struct Echo;
+
+    impl Service for Echo {
+        type Req = u32;
+        type Resp = u32;
+        type Error = ();
+        type Fut = FutureResult<u32, ()>;
+
+        fn call(&self, request: u32) -> Self::Fut {
+            ok(request)
+        }
+    }
+
+    #[test]
+    fn admits_calls_up_to_the_limit_immediately() {
+        let limit = Limit::new(Echo, 2);
+
+        assert_eq!(Ok(Async::Ready(1)), limit.call(1).poll());
+        assert_eq!(Ok(Async::Ready(2)), limit.call(2).poll());
+    }
+
+    #[test]
+    fn calls_past_the_limit_are_held_rather_than_dropped() {
+        let limit = Limit::new(Echo, 1);
+
+        // Hold the single permit open by not letting `first` drop yet.
+        let mut first = limit.call(1);
+        assert_eq!(Ok(Async::Ready(1)), first.poll());
+
+        // `first`'s `Fut` resolved but hasn't been dropped, so its permit
+        // is still held; a second call has to wait rather than being
+        // admitted (and, before this fix, rather than being silently
+        // dropped on the floor).
+        let mut second = limit.call(2);
+        if let LimitFut::Waiting { ref request, .. } = second {
+            assert_eq!(Some(2), *request);
+        } else {
+            panic!("expected the second call to be held back while at capacity");
+        }
+
+        // Releasing the first call's permit lets the second one proceed
+        // the next time it's polled.
+        drop(first);
+        assert_eq!(Ok(Async::Ready(2)), second.poll());
+    }
+}
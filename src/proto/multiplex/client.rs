@@ -0,0 +1,209 @@
+use Service;
+use super::{RequestId, RequestIdSource};
+use proto::pipeline::{self, Error, Message, Transport, NewTransport};
+use reactor::{self, ReactorHandle};
+use util::channel::{Receiver};
+use util::future::{self, Complete, Val};
+use futures::stream::Stream;
+use mio::channel;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Client `Service` for the multiplex protocol.
+///
+/// Initiated requests are tagged with a `RequestId` and sent to the client
+/// dispatch task running on the Reactor. Unlike the pipeline client, the
+/// response that completes a given request's future may arrive in any
+/// order, which allows many requests to be outstanding on the connection at
+/// once.
+pub struct Client<Req, Resp, ReqBody, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          ReqBody: Stream<Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    tx: channel::Sender<(RequestId, Message<Req, ReqBody>, Complete<Resp, E>)>,
+    ids: Arc<RequestIdSource>,
+    closed: Arc<AtomicBool>,
+}
+
+struct Dispatch<T, B, E>
+    where T: Transport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    requests: Receiver<(RequestId, Message<T::In, B>, Complete<T::Out, E>)>,
+    in_flight: HashMap<RequestId, Complete<T::Out, E>>,
+    closed: Arc<AtomicBool>,
+}
+
+/// Connect using the given `NewTransport` and handle the connection using
+/// the multiplex protocol.
+pub fn connect<T, B, E>(reactor: &ReactorHandle, new_transport: T)
+        -> Client<T::In, T::Out, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    let (tx, rx) = channel::channel();
+    let closed = Arc::new(AtomicBool::new(false));
+    let dispatch_closed = closed.clone();
+
+    reactor.oneshot(move || {
+        // Convert to Tokio receiver
+        let rx = try!(Receiver::watch(rx));
+
+        // Create the transport
+        let transport = try!(new_transport.new_transport());
+
+        // Create the client dispatch
+        let dispatch: Dispatch<T::Item, B, E> = Dispatch {
+            requests: rx,
+            in_flight: HashMap::with_capacity(32),
+            closed: dispatch_closed,
+        };
+
+        // Create the multiplexer with the dispatch and transport
+        let multiplex = try!(pipeline::Pipeline::new(dispatch, transport));
+
+        try!(reactor::schedule(multiplex));
+        Ok(())
+    });
+
+    Client { tx: tx, ids: Arc::new(RequestIdSource::new()), closed: closed }
+}
+
+impl<Req, Resp, ReqBody, E> Service for Client<Req, Resp, ReqBody, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          ReqBody: Stream<Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Req = Message<Req, ReqBody>;
+    type Resp = Resp;
+    type Error = E;
+    type Fut = Val<Self::Resp, E>;
+
+    fn call(&self, request: Self::Req) -> Self::Fut {
+        let (c, val) = future::pair();
+        let id = self.ids.next_id();
+
+        if self.tx.send((id, request, c)).is_err() {
+            // The dispatch task is gone; mark the client closed and hand
+            // back an already-failed future instead of leaving the caller
+            // hanging on a completion that will never come.
+            self.closed.store(true, Ordering::Relaxed);
+
+            let (c, val) = future::pair();
+            c.error(Error::Io(broken_pipe()).into());
+            return val;
+        }
+
+        val
+    }
+}
+
+impl<Req, Resp, ReqBody, E> Client<Req, Resp, ReqBody, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          ReqBody: Stream<Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    /// Returns `true` if the connection backing this `Client` has been
+    /// lost; same contract as `pipeline::client::Client::is_closed` (this
+    /// module's `Client`/`Dispatch` mirror the pipeline ones, tagging
+    /// messages with a `RequestId` instead of assuming FIFO ordering — see
+    /// the module docs).
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+impl<Req, Resp, ReqBody, E> Clone for Client<Req, Resp, ReqBody, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          ReqBody: Stream<Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    fn clone(&self) -> Client<Req, Resp, ReqBody, E> {
+        Client { tx: self.tx.clone(), ids: self.ids.clone(), closed: self.closed.clone() }
+    }
+}
+
+impl<T, B, E> pipeline::Dispatch for Dispatch<T, B, E>
+    where T: Transport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    type InMsg = (RequestId, T::In);
+    type InBody = T::BodyIn;
+    type InBodyStream = B;
+    type OutMsg = (RequestId, T::Out);
+    type Error = E;
+
+    fn dispatch(&mut self, response: Self::OutMsg) -> io::Result<()> {
+        let (id, message) = response;
+
+        if let Some(complete) = self.in_flight.remove(&id) {
+            complete.complete(message);
+        } else {
+            // The connection sent a response for a request we have no
+            // record of (likely because it was already completed or
+            // never sent). Log and drop it rather than failing the whole
+            // connection over it.
+            trace!("dropping response for unknown request id {}", id);
+        }
+
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Option<Result<Message<Self::InMsg, Self::InBodyStream>, Self::Error>> {
+        // Try to get a new request frame
+        match self.requests.recv() {
+            Ok(Some((id, request, complete))) => {
+                trace!("received request; id={}", id);
+
+                // Track complete handle
+                self.in_flight.insert(id, complete);
+
+                Some(Ok(request.map(move |req| (id, req))))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                // An error on receive can only happen when the other half
+                // disconnected. Treat this the same as the request stream
+                // ending rather than panicking: stop polling for new
+                // requests and let the in-flight map drain via `Drop`.
+                trace!("client request channel closed: {:?}", e);
+                None
+            }
+        }
+    }
+
+    fn has_in_flight(&self) -> bool {
+        !self.in_flight.is_empty()
+    }
+}
+
+impl<T, B, E> Drop for Dispatch<T, B, E>
+    where T: Transport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    fn drop(&mut self) {
+        // Mark the client closed so callers stop issuing new work.
+        self.closed.store(true, Ordering::Relaxed);
+
+        // Complete any pending requests with an error
+        for (_, complete) in self.in_flight.drain() {
+            let err = Error::Io(broken_pipe());
+            complete.error(err.into());
+        }
+    }
+}
+
+fn broken_pipe() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")
+}
@@ -0,0 +1,66 @@
+//! A multiplexed, request / response based client protocol.
+//!
+//! Unlike `pipeline`, `multiplex` does not assume that responses are
+//! returned in the order that the corresponding requests were sent. Each
+//! outbound request is tagged with a `RequestId` and the response carrying
+//! the same id completes the matching future whenever it arrives, allowing
+//! many requests to be in flight on a single connection at once without
+//! head-of-line blocking.
+//!
+//! `client::Client`/`client::Dispatch` are structurally close to
+//! `pipeline::client`'s (same `Service` impl shape, same closed-flag and
+//! `Drop` handling), differing mainly in the `RequestId`-keyed `HashMap`
+//! in place of pipeline's FIFO `VecDeque`. They're kept as separate,
+//! self-contained types rather than sharing one generic implementation:
+//! each protocol module here is meant to stand alone (no `pub` internals
+//! of one that the other reaches into), the same reason `RequestId`/
+//! `RequestIdSource` live here rather than in a shared location.
+
+pub use self::client::{connect, Client};
+
+mod client;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Uniquely identifies an in-flight request on a multiplexed connection.
+///
+/// The id is assigned when the request is handed to the `Dispatch` and is
+/// expected to be echoed back alongside the response so that it can be
+/// matched up with the `Complete` handle that is waiting on it.
+pub type RequestId = u32;
+
+/// Hands out monotonically increasing `RequestId`s for outbound requests.
+struct RequestIdSource {
+    next: AtomicUsize,
+}
+
+impl RequestIdSource {
+    fn new() -> RequestIdSource {
+        RequestIdSource { next: AtomicUsize::new(0) }
+    }
+
+    fn next_id(&self) -> RequestId {
+        self.next.fetch_add(1, Ordering::Relaxed) as RequestId
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestIdSource;
+    use std::sync::Arc;
+
+    #[test]
+    fn ids_are_monotonic_across_shared_clones() {
+        // `Client::clone` shares one `RequestIdSource` (wrapped in an
+        // `Arc`) between clones rather than handing each clone its own
+        // counter; otherwise two clones would both mint ids starting at 0
+        // and collide in the shared `in_flight` map.
+        let ids = Arc::new(RequestIdSource::new());
+        let a = ids.clone();
+        let b = ids.clone();
+
+        assert_eq!(0, a.next_id());
+        assert_eq!(1, b.next_id());
+        assert_eq!(2, a.next_id());
+    }
+}
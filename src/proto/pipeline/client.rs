@@ -3,10 +3,13 @@ use super::{pipeline, Error, Message, Transport, NewTransport};
 use reactor::{self, ReactorHandle};
 use util::channel::{Receiver};
 use util::future::{self, Complete, Val};
+use futures::{Async, Poll};
 use futures::stream::Stream;
 use mio::channel;
 use std::io;
 use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// Client `Service` for the pipeline protocol.
 ///
@@ -20,6 +23,7 @@ pub struct Client<Req, Resp, ReqBody, E>
           E: From<Error<E>> + Send + 'static,
 {
     tx: channel::Sender<(Message<Req, ReqBody>, Complete<Resp, E>)>,
+    closed: Arc<AtomicBool>,
 }
 
 struct Dispatch<T, B, E>
@@ -29,6 +33,7 @@ struct Dispatch<T, B, E>
 {
     requests: Receiver<(Message<T::In, B>, Complete<T::Out, E>)>,
     in_flight: VecDeque<Complete<T::Out, E>>,
+    closed: Arc<AtomicBool>,
 }
 
 /// Connect to the given `addr` and handle using the given Transport and protocol pipelining.
@@ -39,6 +44,8 @@ pub fn connect<T, B, E>(reactor: &ReactorHandle, new_transport: T)
           E: From<Error<E>> + Send + 'static,
 {
     let (tx, rx) = channel::channel();
+    let closed = Arc::new(AtomicBool::new(false));
+    let dispatch_closed = closed.clone();
 
     reactor.oneshot(move || {
         // Convert to Tokio receiver
@@ -51,6 +58,7 @@ pub fn connect<T, B, E>(reactor: &ReactorHandle, new_transport: T)
         let dispatch: Dispatch<T::Item, B, E> = Dispatch {
             requests: rx,
             in_flight: VecDeque::with_capacity(32),
+            closed: dispatch_closed,
         };
 
         // Create the pipeline with the dispatch and transport
@@ -60,7 +68,7 @@ pub fn connect<T, B, E>(reactor: &ReactorHandle, new_transport: T)
         Ok(())
     });
 
-    Client { tx: tx }
+    Client { tx: tx, closed: closed }
 }
 
 impl<Req, Resp, ReqBody, E> Service for Client<Req, Resp, ReqBody, E>
@@ -77,13 +85,36 @@ impl<Req, Resp, ReqBody, E> Service for Client<Req, Resp, ReqBody, E>
     fn call(&self, request: Self::Req) -> Self::Fut {
         let (c, val) = future::pair();
 
-        // TODO: handle error
-        self.tx.send((request, c)).ok().unwrap();
+        if self.tx.send((request, c)).is_err() {
+            // The dispatch task is gone, so the completion handle above was
+            // dropped along with the send and will never be fulfilled.
+            // Mark the client closed and hand back a fresh, already-failed
+            // future instead of leaving the caller hanging.
+            self.closed.store(true, Ordering::Relaxed);
+
+            let (c, val) = future::pair();
+            c.error(Error::Io(broken_pipe()).into());
+            return val;
+        }
 
         val
     }
 }
 
+impl<Req, Resp, ReqBody, E> Client<Req, Resp, ReqBody, E>
+    where Req: Send + 'static,
+          Resp: Send + 'static,
+          ReqBody: Stream<Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    /// Returns `true` if the connection backing this `Client` has been
+    /// lost. Once closed, a `Client` never recovers; `call` will keep
+    /// returning futures that immediately fail with `Error::Io`.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
 impl<Req, Resp, ReqBody, E> Clone for Client<Req, Resp, ReqBody, E>
     where Req: Send + 'static,
           Resp: Send + 'static,
@@ -91,7 +122,7 @@ impl<Req, Resp, ReqBody, E> Clone for Client<Req, Resp, ReqBody, E>
           E: From<Error<E>> + Send + 'static,
 {
     fn clone(&self) -> Client<Req, Resp, ReqBody, E> {
-        Client { tx: self.tx.clone() }
+        Client { tx: self.tx.clone(), closed: self.closed.clone() }
     }
 }
 
@@ -131,9 +162,13 @@ impl<T, B, E> pipeline::Dispatch for Dispatch<T, B, E>
             Ok(None) => None,
             Err(e) => {
                 // An error on receive can only happen when the other half
-                // disconnected. In this case, the client needs to be
-                // shutdown
-                panic!("unimplemented error handling: {:?}", e);
+                // disconnected. Treat this the same as the request stream
+                // ending: stop polling for new requests and let the
+                // pipeline wind down, failing any in-flight requests via
+                // `Drop` rather than taking the reactor thread down with
+                // it.
+                trace!("client request channel closed: {:?}", e);
+                None
             }
         }
     }
@@ -149,6 +184,9 @@ impl<T, B, E> Drop for Dispatch<T, B, E>
           E: From<Error<E>> + Send + 'static,
 {
     fn drop(&mut self) {
+        // Mark the client closed so callers stop issuing new work.
+        self.closed.store(true, Ordering::Relaxed);
+
         // Complete any pending requests with an error
         while let Some(complete) = self.in_flight.pop_front() {
             let err = Error::Io(broken_pipe());
@@ -160,3 +198,239 @@ impl<T, B, E> Drop for Dispatch<T, B, E>
 fn broken_pipe() -> io::Error {
     io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe")
 }
+
+/// Drive `requests` through `client`, issuing each one as soon as it is
+/// available and yielding the responses as a `Stream`, in request order.
+///
+/// Responses can be returned by the transport out of order relative to
+/// when they resolve (a later request's `Val` may complete first), so the
+/// outstanding `Val`s are polled front-to-back rather than just taking
+/// whichever resolves first; this keeps `CallAll`'s output in the same
+/// order the requests were read off of `requests`.
+pub fn call_all<C, S>(client: C, requests: S) -> CallAll<C, S>
+    where C: Service,
+          S: Stream<Item = C::Req>,
+          C::Error: From<S::Error>,
+{
+    CallAll {
+        client: client,
+        requests: requests,
+        requests_done: false,
+        in_flight: VecDeque::new(),
+    }
+}
+
+/// Stream returned by the `call_all` combinator.
+pub struct CallAll<C, S>
+    where C: Service,
+          S: Stream<Item = C::Req>,
+          C::Error: From<S::Error>,
+{
+    client: C,
+    requests: S,
+    requests_done: bool,
+    in_flight: VecDeque<C::Fut>,
+}
+
+impl<C, S> Stream for CallAll<C, S>
+    where C: Service,
+          S: Stream<Item = C::Req>,
+          C::Error: From<S::Error>,
+{
+    type Item = C::Resp;
+    type Error = C::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        // Pull as many requests as are ready off of the input stream,
+        // issuing each one through the client right away.
+        while !self.requests_done {
+            match try!(self.requests.poll()) {
+                Async::Ready(Some(request)) => {
+                    self.in_flight.push_back(self.client.call(request));
+                }
+                Async::Ready(None) => self.requests_done = true,
+                Async::NotReady => break,
+            }
+        }
+
+        // Only the oldest outstanding response is allowed to complete the
+        // stream; this is what keeps responses in request order even
+        // though the underlying `Val`s may resolve out of order.
+        match self.in_flight.front_mut() {
+            Some(fut) => {
+                // Poll the oldest future directly rather than through
+                // `try_ready!`, which would `return` on error and skip the
+                // `pop_front` below, leaving an already-resolved future at
+                // the front of `in_flight` to be polled again on the next
+                // call into this `Stream` — not allowed by the `Future`
+                // contract.
+                match fut.poll() {
+                    Ok(Async::Ready(resp)) => {
+                        self.in_flight.pop_front();
+                        Ok(Async::Ready(Some(resp)))
+                    }
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Err(e) => {
+                        self.in_flight.pop_front();
+                        Err(e)
+                    }
+                }
+            }
+            None if self.requests_done => Ok(Async::Ready(None)),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::call_all;
+    use Service;
+    use futures::{Async, Future, Poll};
+    use futures::stream::Stream;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    /// A request stream the test drives directly, rather than one backed by
+    /// a real transport.
+    struct Requests<T, E> {
+        items: VecDeque<Result<T, E>>,
+    }
+
+    impl<T, E> Stream for Requests<T, E> {
+        type Item = T;
+        type Error = E;
+
+        fn poll(&mut self) -> Poll<Option<T>, E> {
+            match self.items.pop_front() {
+                Some(Ok(item)) => Ok(Async::Ready(Some(item))),
+                Some(Err(e)) => Err(e),
+                None => Ok(Async::Ready(None)),
+            }
+        }
+    }
+
+    /// A `Future` whose result is set from outside, after the `Service` that
+    /// returned it has already handed it back to `CallAll` — lets the test
+    /// resolve requests in whatever order it wants, independent of the
+    /// order they were issued in.
+    struct Slot<T, E> {
+        value: Arc<Mutex<Option<Result<T, E>>>>,
+        consumed: bool,
+    }
+
+    impl<T: Clone, E: Clone> Future for Slot<T, E> {
+        type Item = T;
+        type Error = E;
+
+        fn poll(&mut self) -> Poll<T, E> {
+            assert!(!self.consumed, "Slot polled again after already resolving");
+
+            match self.value.lock().unwrap().clone() {
+                Some(Ok(v)) => {
+                    self.consumed = true;
+                    Ok(Async::Ready(v))
+                }
+                Some(Err(e)) => {
+                    self.consumed = true;
+                    Err(e)
+                }
+                None => Ok(Async::NotReady),
+            }
+        }
+    }
+
+    fn slot<T, E>() -> (Arc<Mutex<Option<Result<T, E>>>>, Slot<T, E>) {
+        let value = Arc::new(Mutex::new(None));
+        let fut = Slot { value: value.clone(), consumed: false };
+        (value, fut)
+    }
+
+    /// A `Service` whose calls never resolve on their own; the test
+    /// completes them via the `Arc<Mutex<Option<...>>>` handles that each
+    /// call records in `calls`, in whatever order it likes.
+    struct Recorder {
+        calls: Mutex<Vec<Arc<Mutex<Option<Result<u32, ()>>>>>>,
+    }
+
+    impl Recorder {
+        fn new() -> Recorder {
+            Recorder { calls: Mutex::new(Vec::new()) }
+        }
+    }
+
+    impl Service for Recorder {
+        type Req = u32;
+        type Resp = u32;
+        type Error = ();
+        type Fut = Slot<u32, ()>;
+
+        fn call(&self, request: u32) -> Slot<u32, ()> {
+            let (value, fut) = slot();
+            *value.lock().unwrap() = None;
+            self.calls.lock().unwrap().push(value);
+            let _ = request;
+            fut
+        }
+    }
+
+    fn complete(calls: &Mutex<Vec<Arc<Mutex<Option<Result<u32, ()>>>>>>, i: usize, result: Result<u32, ()>) {
+        *calls.lock().unwrap()[i].lock().unwrap() = Some(result);
+    }
+
+    #[test]
+    fn responses_return_in_request_order_despite_out_of_order_resolution() {
+        let client = Recorder::new();
+        let requests = Requests { items: vec![Ok(1), Ok(2), Ok(3)].into() };
+        let mut stream = call_all(client, requests);
+
+        // Pulls all three requests off of `requests` and issues them
+        // immediately, even though none have resolved yet.
+        assert_eq!(Ok(Async::NotReady), stream.poll());
+        assert_eq!(3, stream.client.calls.lock().unwrap().len());
+
+        // Resolve out of order: the last request first, then the middle
+        // one; the first (oldest) request is still the one `CallAll` is
+        // waiting on, so nothing should be yielded yet.
+        complete(&stream.client.calls, 2, Ok(30));
+        complete(&stream.client.calls, 1, Ok(20));
+        assert_eq!(Ok(Async::NotReady), stream.poll());
+
+        // Resolving the oldest request unblocks it, and only it.
+        complete(&stream.client.calls, 0, Ok(10));
+        assert_eq!(Ok(Async::Ready(Some(10))), stream.poll());
+        assert_eq!(Ok(Async::Ready(Some(20))), stream.poll());
+        assert_eq!(Ok(Async::Ready(Some(30))), stream.poll());
+    }
+
+    #[test]
+    fn error_on_front_future_pops_it_instead_of_repolling() {
+        let client = Recorder::new();
+        let requests = Requests { items: vec![Ok(1), Ok(2)].into() };
+        let mut stream = call_all(client, requests);
+
+        assert_eq!(Ok(Async::NotReady), stream.poll());
+
+        complete(&stream.client.calls, 0, Err(()));
+        // `Slot::poll` panics if polled again after resolving, so this
+        // would panic if the front future weren't popped on error and got
+        // handed back to `fut.poll()` on the next call into the stream.
+        assert_eq!(Err(()), stream.poll());
+
+        complete(&stream.client.calls, 1, Ok(2));
+        assert_eq!(Ok(Async::Ready(Some(2))), stream.poll());
+    }
+
+    #[test]
+    fn stream_completes_once_input_ends_and_in_flight_drains() {
+        let client = Recorder::new();
+        let requests = Requests { items: vec![Ok(1)].into() };
+        let mut stream = call_all(client, requests);
+
+        assert_eq!(Ok(Async::NotReady), stream.poll());
+
+        complete(&stream.client.calls, 0, Ok(1));
+        assert_eq!(Ok(Async::Ready(Some(1))), stream.poll());
+        assert_eq!(Ok(Async::Ready(None)), stream.poll());
+    }
+}
@@ -0,0 +1,178 @@
+//! Optional expect/continue flow control for streaming request bodies.
+//!
+//! Today `pipeline::Server` forwards `Frame::Body` chunks to the body
+//! `Stream` handed to the `ServerService` regardless of whether the service
+//! ever looks at it (see `test_pipeline_streaming_body_without_consuming`).
+//! For large bodies that a service may reject outright, that means the
+//! producer streams data nobody reads.
+//!
+//! `Gate` is the withhold/drain mechanism a `MessageWithBody` frame opts
+//! into: body chunks arriving from the transport are buffered rather than
+//! handed to the service until something calls `signal_continue` (the
+//! service has started consuming the body) or `drain` (the service
+//! responded/errored without ever consuming it, so the buffered chunks,
+//! and any still to come, are dropped instead of being delivered late).
+//!
+//! NOTE: wiring `Gate` into the frame loop so that it is actually
+//! constructed per-request and consulted before a `Frame::Body` is
+//! forwarded is a change to `pipeline::Server` itself, which lives outside
+//! this chunk of the tree (only `pipeline::client` and this module are
+//! present here). What's implemented here is the gate itself, with real
+//! behavior and tests, ready for that frame loop to drive. The still-missing
+//! wiring is tracked by the `#[ignore]`d
+//! `test_expect_continue_body_withheld_until_consumed` and
+//! `test_expect_continue_body_drained_when_response_sent_first` in
+//! `test_pipeline_server.rs`, rather than just this doc comment.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Mutex;
+
+/// Whether a request's body chunks, as they arrive from the transport, are
+/// withheld from the service until the service asks for the body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Continue {
+    /// Body frames may be forwarded to the service as soon as they
+    /// arrive; this is today's behavior.
+    Immediate,
+    /// Body frames are withheld until the service either consumes the
+    /// body (continue) or completes without doing so (drain and drop).
+    AwaitAck,
+}
+
+impl Default for Continue {
+    fn default() -> Continue {
+        Continue::Immediate
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Mode {
+    AwaitAck,
+    Continued,
+    Draining,
+}
+
+struct State<T> {
+    buffered: VecDeque<T>,
+    mode: Mode,
+}
+
+/// Withholds a single request's body chunks until the service either
+/// starts consuming them or completes without doing so.
+pub struct Gate<T> {
+    state: Mutex<State<T>>,
+}
+
+impl<T> Gate<T> {
+    /// Create a `Gate` that behaves per `policy`: `Immediate` forwards
+    /// chunks as soon as they are pushed, `AwaitAck` buffers them until
+    /// `signal_continue` or `drain` is called.
+    pub fn new(policy: Continue) -> Gate<T> {
+        let mode = match policy {
+            Continue::Immediate => Mode::Continued,
+            Continue::AwaitAck => Mode::AwaitAck,
+        };
+
+        Gate {
+            state: Mutex::new(State {
+                buffered: VecDeque::new(),
+                mode: mode,
+            }),
+        }
+    }
+
+    /// Called by the frame loop when a body chunk arrives from the
+    /// transport. While awaiting ack or draining, the chunk is held or
+    /// dropped rather than being handed straight to the service.
+    pub fn push(&self, chunk: T) {
+        let mut state = self.state.lock().unwrap();
+
+        match state.mode {
+            Mode::Draining => {
+                // The service already completed without consuming the
+                // body; drop chunks on the floor instead of buffering them
+                // forever.
+            }
+            Mode::AwaitAck | Mode::Continued => state.buffered.push_back(chunk),
+        }
+    }
+
+    /// The service has started consuming the body: release any buffered
+    /// chunks and forward chunks as they arrive from here on.
+    pub fn signal_continue(&self) {
+        self.state.lock().unwrap().mode = Mode::Continued;
+    }
+
+    /// The service responded or errored without ever consuming the body:
+    /// drop whatever is buffered and drain future chunks instead of
+    /// delivering them late.
+    pub fn drain(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.buffered.clear();
+        state.mode = Mode::Draining;
+    }
+
+    /// Take the chunks that are ready to be forwarded to the service.
+    /// Returns nothing while still awaiting ack or draining.
+    pub fn take_ready(&self) -> VecDeque<T> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.mode {
+            Mode::Continued => mem::replace(&mut state.buffered, VecDeque::new()),
+            Mode::AwaitAck | Mode::Draining => VecDeque::new(),
+        }
+    }
+
+    /// `true` once the service has completed without consuming the body.
+    pub fn is_draining(&self) -> bool {
+        self.state.lock().unwrap().mode == Mode::Draining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Continue, Gate};
+
+    #[test]
+    fn immediate_forwards_chunks_as_they_are_pushed() {
+        let gate = Gate::new(Continue::Immediate);
+
+        gate.push(1);
+        gate.push(2);
+
+        assert_eq!(vec![1, 2], gate.take_ready().into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn await_ack_withholds_chunks_until_continue_is_signaled() {
+        let gate = Gate::new(Continue::AwaitAck);
+
+        gate.push(1);
+        gate.push(2);
+        assert!(gate.take_ready().is_empty());
+
+        gate.signal_continue();
+        assert_eq!(vec![1, 2], gate.take_ready().into_iter().collect::<Vec<_>>());
+
+        // Further chunks are forwarded immediately once continued.
+        gate.push(3);
+        assert_eq!(vec![3], gate.take_ready().into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn drain_drops_buffered_and_future_chunks() {
+        let gate = Gate::new(Continue::AwaitAck);
+
+        gate.push(1);
+        gate.drain();
+
+        assert!(gate.is_draining());
+        assert!(gate.take_ready().is_empty());
+
+        // A chunk that arrives after the service already responded is
+        // dropped rather than buffered forever.
+        gate.push(2);
+        assert!(gate.take_ready().is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+//! A `Service` middleware that transparently reconnects a pipelined client
+//! when its transport is lost.
+//!
+//! `proto::pipeline::connect` hands back a `Client` whose backing dispatch
+//! task dies the moment the transport is lost; every `Complete` in flight
+//! is failed and the `Client` handle is permanently useless afterwards
+//! (`Client::is_closed` reports this). `Reconnect` wraps a `NewTransport`
+//! and, on noticing that its current `Client` has closed, transparently
+//! re-runs `pipeline::connect` to establish a fresh one, so callers keep
+//! talking to a single stable `Service` across disconnects.
+//!
+//! `Reconnect` tracks a coarse `ConnState` (`Idle` / `Connecting` /
+//! `Connected`, see below) so that `when_connecting` governs every call made
+//! during the window a (re)connect is outstanding, not just the one call
+//! that happened to notice the old client had closed.
+
+use Service;
+use proto::pipeline::{self, Client as PipelineClient, Error, Message, NewTransport};
+use reactor::ReactorHandle;
+use util::future::{self, Val};
+use futures::stream::Stream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What calls made while `Reconnect` is `Connecting` (see `ConnState`)
+/// should do with their request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhenConnecting {
+    /// Hand the request to the `Client` right away; it will sit in the
+    /// dispatch task's queue until the transport is actually up. If that
+    /// `Client` has already closed again within `backoff` of its connect
+    /// attempt, no new attempt is made yet and the call fails with
+    /// `Error::Io` like any call on a closed `Client`.
+    Buffer,
+    /// Immediately fail the call with `Error::Io(broken_pipe())` instead of
+    /// queueing it on a client that may not be connected yet.
+    Error,
+}
+
+/// Coarse connection state `Reconnect` tracks for its current `Client`.
+///
+/// There's no notification from `pipeline::connect` when the transport
+/// actually finishes connecting — `Client::is_closed` only ever reports
+/// failure, never success — so `Connecting` is left the moment `backoff`
+/// has elapsed since the attempt that produced the current `Client` without
+/// it having closed. That's a heuristic, not a real handshake-complete
+/// signal, but it bounds how long `when_connecting` is applied to a window
+/// that actually looks like "still reconnecting" rather than just the one
+/// call that happened to notice the old client was closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConnState {
+    /// No connection attempt has been made yet.
+    Idle,
+    /// A `Client` was (re)connected within the last `backoff`, whether or
+    /// not it has been observed closed since.
+    Connecting,
+    /// At least `backoff` has passed since the last (re)connect attempt
+    /// and the `Client` is still open.
+    Connected,
+}
+
+/// A `Service` that wraps a `NewTransport` and automatically reconnects the
+/// underlying pipeline `Client` whenever the connection is lost.
+pub struct Reconnect<T, B, E>
+    where T: NewTransport<Error = E> + Clone + Send + 'static,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    new_transport: T,
+    reactor: ReactorHandle,
+    when_connecting: WhenConnecting,
+    backoff: Duration,
+    inner: Mutex<Inner<T, B, E>>,
+}
+
+struct Inner<T, B, E>
+    where T: NewTransport<Error = E>,
+          B: Stream<Item = T::BodyIn, Error = E>,
+          E: From<Error<E>> + Send + 'static,
+{
+    client: Option<PipelineClient<T::In, T::Out, B, E>>,
+    last_attempt: Option<Instant>,
+    state: ConnState,
+}
+
+impl<T, B, E> Reconnect<T, B, E>
+    where T: NewTransport<Error = E> + Clone + Send + 'static,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    /// Create a `Reconnect` that will lazily connect on the first call,
+    /// backing off for `backoff` between reconnect attempts.
+    pub fn new(reactor: ReactorHandle, new_transport: T, backoff: Duration) -> Reconnect<T, B, E> {
+        Reconnect {
+            new_transport: new_transport,
+            reactor: reactor,
+            when_connecting: WhenConnecting::Buffer,
+            backoff: backoff,
+            inner: Mutex::new(Inner {
+                client: None,
+                last_attempt: None,
+                state: ConnState::Idle,
+            }),
+        }
+    }
+
+    /// Configure what calls made while `Connecting` (see `ConnState`) do
+    /// with their request.
+    pub fn when_connecting(mut self, policy: WhenConnecting) -> Reconnect<T, B, E> {
+        self.when_connecting = policy;
+        self
+    }
+
+    /// Returns the `Client` to issue a request against, along with whether
+    /// the connection is currently `Connecting` and `when_connecting`
+    /// should therefore be applied to this call.
+    fn client(&self) -> (PipelineClient<T::In, T::Out, B, E>, bool) {
+        let mut inner = self.inner.lock().unwrap();
+
+        let needs_connect = match inner.client {
+            Some(ref client) => client.is_closed(),
+            None => true,
+        };
+        let within_backoff = within_backoff(inner.last_attempt, self.backoff);
+
+        if needs_connect && !within_backoff {
+            // Either there's no client yet, or the one we have has closed
+            // and we're clear of the backoff from the attempt that created
+            // it: kick off a fresh reconnect.
+            let client = pipeline::connect(&self.reactor, self.new_transport.clone());
+            inner.client = Some(client.clone());
+            inner.last_attempt = Some(Instant::now());
+            inner.state = ConnState::Connecting;
+        } else if !needs_connect && !within_backoff {
+            // The client is open and it's been at least `backoff` since we
+            // (re)connected it without seeing it close: treat the
+            // connection as established.
+            inner.state = ConnState::Connected;
+        }
+        // The remaining case, `within_backoff`, leaves `state` as
+        // `Connecting` (an attempt has been made, so it is never `Idle`
+        // here) regardless of `needs_connect`: a client that's open
+        // but still inside the backoff window from its own connect
+        // attempt hasn't had time to prove itself yet, so every call made
+        // against it during that window is still "connecting" as far as
+        // `when_connecting` is concerned, not just the one that happened
+        // to trigger the attempt.
+
+        let connecting = inner.state == ConnState::Connecting;
+        (inner.client.as_ref().unwrap().clone(), connecting)
+    }
+}
+
+impl<T, B, E> Service for Reconnect<T, B, E>
+    where T: NewTransport<Error = E> + Clone + Send + 'static,
+          B: Stream<Item = T::BodyIn, Error = E> + Send + 'static,
+          E: From<Error<E>> + Send + 'static,
+{
+    type Req = Message<T::In, B>;
+    type Resp = T::Out;
+    type Error = E;
+    type Fut = Val<T::Out, E>;
+
+    fn call(&self, request: Self::Req) -> Self::Fut {
+        let (client, connecting) = self.client();
+
+        if connecting && self.when_connecting == WhenConnecting::Error {
+            let (c, val) = future::pair();
+            c.error(Error::Io(broken_pipe()).into());
+            return val;
+        }
+
+        client.call(request)
+    }
+}
+
+fn broken_pipe() -> ::std::io::Error {
+    ::std::io::Error::new(::std::io::ErrorKind::BrokenPipe, "broken pipe")
+}
+
+/// `true` if the last reconnect attempt was recent enough that another one
+/// shouldn't be kicked off yet.
+fn within_backoff(last_attempt: Option<Instant>, backoff: Duration) -> bool {
+    last_attempt.map(|at| at.elapsed() < backoff).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::within_backoff;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn no_previous_attempt_is_never_backing_off() {
+        assert!(!within_backoff(None, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn recent_attempt_is_backing_off() {
+        assert!(within_backoff(Some(Instant::now()), Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn expired_attempt_is_not_backing_off() {
+        let long_ago = Instant::now() - Duration::from_secs(3600);
+        assert!(!within_backoff(Some(long_ago), Duration::from_millis(1)));
+    }
+}
@@ -362,6 +362,25 @@ fn test_pipeline_streaming_body_without_consuming() {
 fn test_transport_error_during_body_stream() {
 }
 
+#[test]
+#[ignore]
+fn test_expect_continue_body_withheld_until_consumed() {
+    // Once `proto::pipeline::expect_continue::Gate` is wired into
+    // `pipeline::Server`'s frame loop, a request flagged for expect-continue
+    // should not see its body frames forwarded to the service's `Stream`
+    // until the service actually polls it.
+    unimplemented!();
+}
+
+#[test]
+#[ignore]
+fn test_expect_continue_body_drained_when_response_sent_first() {
+    // If the service responds (or errors) without ever consuming the body,
+    // the withheld body frames should be drained from the transport and
+    // dropped rather than handed to the service late.
+    unimplemented!();
+}
+
 #[test]
 fn test_streaming_response_body() {
     let (tx, rx) = future::channel::<u32, io::Error>();